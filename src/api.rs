@@ -1,42 +1,233 @@
+use crate::config::{Config, Retry};
 use crate::error::{Error, Result};
-use crate::traits::*;
+use crate::progress::FileProgress;
+use crate::string::next_link;
+use crate::types::{Course, CourseFilter, File, Folder, Profile};
+use crate::{BINARY_NAME, VERSION};
 use futures::{FutureExt, StreamExt};
-use reqwest::Response;
+use reqwest::header::{HeaderMap, LINK, RETRY_AFTER};
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
-use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// Number of items requested per page of a list endpoint.
+const PER_PAGE: u32 = 118;
+
+/// Maximum number of pages followed for a single list endpoint.
+const MAX_PAGES: usize = 100;
+
+/// Longest wait for a connection to be established.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for an API response, or for the next chunk of a
+/// download.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest delay between two retries, before jitter, and longest
+/// `Retry-After` waited for.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Canvas' quota header names. Every request costs some quota, which
+/// refills over time, and requests are refused with a 403 once it
+/// runs out.
+/// https://canvas.instructure.com/doc/api/file.throttling.html
+const RATE_LIMIT_REMAINING: &str = "x-rate-limit-remaining";
+const REQUEST_COST: &str = "x-request-cost";
+
+/// Requests slow down once the remaining quota drops below this.
+const LOW_QUOTA: f64 = 200.0;
+
+/// Longest pause before a request, when the quota is about to run out.
+const MAX_THROTTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// Quota that Canvas gives back per second, by its defaults.
+const QUOTA_REFILL_RATE: f64 = 10.0;
+
+/// Quota that Canvas charges up front for every request, and refunds
+/// once the request is done.
+const QUOTA_UPFRONT_COST: f64 = 50.0;
 
 #[derive(Debug, Clone)]
 pub struct Api {
+    /// Shared by every request, to reuse connections.
+    client: Client,
     /// Canvas access token
     access_token: String,
+    /// Url of the Canvas instance, without a trailing slash.
+    base_url: String,
+    retry: Retry,
+    /// Last known remaining quota, shared across clones.
+    quota: Arc<Mutex<Option<Quota>>>,
+}
+
+/// Remaining quota, as last reported by Canvas or reserved by us.
+#[derive(Debug, Clone, Copy)]
+struct Quota {
+    remaining: f64,
+    at: Instant,
+}
+
+impl Quota {
+    fn new(remaining: f64) -> Self {
+        Self { remaining, at: Instant::now() }
+    }
+
+    /// Estimate of the remaining quota by now, counting what Canvas
+    /// has given back since.
+    fn now(&self) -> f64 {
+        let refilled = self.at.elapsed().as_secs_f64() * QUOTA_REFILL_RATE;
+        self.remaining + refilled
+    }
 }
 
 impl Api {
-    pub fn new(access_token: &str) -> Self {
-        Self { access_token: access_token.to_string() }
+    pub fn new(config: &Config) -> Result<Self> {
+        let user_agent =
+            format!("{BINARY_NAME}/{}", VERSION.unwrap_or("unknown"));
+        let mut client = Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(CONNECT_TIMEOUT);
+        // without a configured proxy, reqwest picks up HTTPS_PROXY and
+        // friends from the environment by itself.
+        if let Some(url) = config.proxy() {
+            let proxy = Proxy::all(url).map_err(Error::InvalidProxy)?;
+            client = client.proxy(proxy.no_proxy(NoProxy::from_env()));
+        }
+        for path in config.ca_certs() {
+            let err = |e: String| Error::InvalidCaCert(path.clone(), e);
+            let pem = std::fs::read(&path).map_err(|e| err(e.to_string()))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .map_err(|e| err(e.to_string()))?;
+            for cert in certs {
+                client = client.add_root_certificate(cert);
+            }
+        }
+        Ok(Self {
+            client: client.build()?,
+            access_token: config.access_token().to_string(),
+            base_url: config.base_url().to_string(),
+            retry: config.retry(),
+            quota: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Build the full url of a Canvas API endpoint.
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/api/v1/{path}", self.base_url)
     }
 
     /// Send off an authorized request.
-    async fn get(&self, url: &str) -> Result<Response> {
-        let client = reqwest::Client::new();
-        let req = client.get(url);
-        Ok(req
-            .bearer_auth(&self.access_token)
-            .query(&[("per_page", "118")])
-            .send()
-            .await?)
+    async fn get(&self, url: &str, query: &[(&str, &str)]) -> Result<Response> {
+        let req = self.client.get(url).timeout(READ_TIMEOUT);
+        let req = req.bearer_auth(&self.access_token).query(query);
+        let res = self.send(req).await?;
+        if is_throttled(&res) {
+            return Err(Error::RateLimited { url: url.to_string() });
+        }
+        Ok(res)
     }
 
-    /// Get the data of a request in json form.
-    async fn json(&self, url: &str) -> Result<Value> {
-        let json = self.get(url).await?.json::<Value>().await?;
+    /// Pauses if the remaining quota is running low, for longer the
+    /// closer it is to running out. Reserves the up front cost of the
+    /// request, so that concurrent requests slow down too.
+    async fn throttle(&self) {
+        let remaining = {
+            let mut quota = self.quota.lock().unwrap();
+            let Some(remaining) = quota.map(|v| v.now()) else {
+                return;
+            };
+            *quota = Some(Quota::new(remaining - QUOTA_UPFRONT_COST));
+            remaining
+        };
+        if remaining >= LOW_QUOTA {
+            return;
+        }
+        let ratio = 1.0 - remaining.max(0.0) / LOW_QUOTA;
+        let delay = MAX_THROTTLE_DELAY.mul_f64(ratio);
+        log::debug!("[API::THROTTLE] {remaining:.1} quota left, {delay:?}");
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Records the remaining quota reported by Canvas.
+    fn update_quota(&self, headers: &HeaderMap) {
+        let Some(remaining) = header_f64(headers, RATE_LIMIT_REMAINING) else {
+            return;
+        };
+        if let Some(cost) = header_f64(headers, REQUEST_COST) {
+            log::debug!(
+                "[API::QUOTA] cost {cost:.1}, remaining {remaining:.1}"
+            );
+        }
+        *self.quota.lock().unwrap() = Some(Quota::new(remaining));
+    }
+
+    /// Sends a request, retrying connection errors and 5xx and 429
+    /// responses with exponential backoff. Slows down as Canvas'
+    /// quota runs low, and retries requests refused for exceeding it.
+    async fn send(&self, req: RequestBuilder) -> reqwest::Result<Response> {
+        // only requests to Canvas itself count towards the quota.
+        let to_canvas = req
+            .try_clone()
+            .and_then(|v| v.build().ok())
+            .is_some_and(|v| v.url().as_str().starts_with(&self.base_url));
+        let mut attempt = 0;
+        loop {
+            if to_canvas {
+                self.throttle().await;
+            }
+            // only bodiless GET requests are sent, so cloning never fails.
+            let res = req.try_clone().expect("clonable request").send().await;
+            if let Ok(v) = &res {
+                self.update_quota(v.headers());
+            }
+            let retry_after = match &res {
+                Ok(v) if is_transient_status(v.status()) => {
+                    retry_after(v.headers())
+                }
+                Ok(v) if is_throttled(v) => None,
+                Err(e) if is_transient_err(e) => None,
+                _ => return res,
+            };
+            if attempt >= self.retry.retries {
+                return res;
+            }
+            // a Retry-After longer than the cap is not waited out in full.
+            let delay = match retry_after {
+                Some(v) => v.min(MAX_RETRY_DELAY),
+                None => backoff(&self.retry, attempt),
+            };
+            attempt += 1;
+            match &res {
+                Ok(v) => log::debug!(
+                    "[API::RETRY] {attempt}/{} in {delay:?}: {} {}",
+                    self.retry.retries,
+                    v.status(),
+                    v.url()
+                ),
+                Err(e) => log::debug!(
+                    "[API::RETRY] {attempt}/{} in {delay:?}: {e}",
+                    self.retry.retries
+                ),
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Fails if the json returned by Canvas is an error message.
+    fn check(&self, json: Value, url: &str) -> Result<Value> {
         if json["errors"][0]["message"].eq("Invalid access token.") {
-            return Err(Error::InvalidToken);
+            let base_url = self.base_url.to_string();
+            return Err(Error::InvalidToken { base_url });
         }
-        if json["errors"].as_array().map_or(false, |v| v.len() > 0) {
-            let msg = json["errors"][0]["message"].to_str();
-            return Err(Error::CanvasError {
+        if json["errors"].as_array().is_some_and(|v| !v.is_empty()) {
+            let msg = json["errors"][0]["message"].as_str().unwrap_or("");
+            return Err(Error::Canvas {
                 msg: msg.to_string(),
                 url: url.to_string(),
             });
@@ -44,55 +235,263 @@ impl Api {
         Ok(json)
     }
 
+    /// Parse json returned by Canvas into `T`.
+    fn parse<T: DeserializeOwned>(&self, json: Value, url: &str) -> Result<T> {
+        serde_json::from_value(self.check(json, url)?).map_err(|err| {
+            Error::UnexpectedResponse { url: url.to_string(), err }
+        })
+    }
+
+    /// Get the data of a request in json form.
+    async fn json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let json = self.get(url, &[]).await?.json::<Value>().await?;
+        self.parse(json, url)
+    }
+
+    /// Get the data of a list endpoint in json form. Follows the
+    /// `Link` header across pages and returns one concatenated list.
+    async fn json_list<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<T>> {
+        let per_page = PER_PAGE.to_string();
+        let query = [&[("per_page", per_page.as_str())], query].concat();
+        let mut response = self.get(url, &query).await?;
+        let mut list = vec![];
+        for page in 1..=MAX_PAGES {
+            log::debug!("[API::PAGE] {page} {}", response.url());
+            let next = response
+                .headers()
+                .get(LINK)
+                .and_then(|v| v.to_str().ok())
+                .and_then(next_link)
+                .map(|v| v.to_string());
+            let json = response.json::<Value>().await?;
+            list.extend(self.parse::<Vec<T>>(json, url)?);
+            match next {
+                None => return Ok(list),
+                Some(_) if page == MAX_PAGES => break,
+                Some(next) => response = self.get(&next, &[]).await?,
+            }
+        }
+        log::warn!("[API::PAGE] stopped after {MAX_PAGES} pages of {url}");
+        Ok(list)
+    }
+
     /// Prints basic information about the user to make sure that the
     /// access token is present and valid.
-    pub async fn profile(&self) -> Result<Value> {
-        self.json("https://canvas.nus.edu.sg/api/v1/users/self/profile").await
+    pub async fn profile(&self) -> Result<Profile> {
+        self.json(&self.endpoint("users/self/profile")).await
     }
 
-    /// Get a list of courses of the current user.
-    pub async fn courses(&self) -> Result<Value> {
-        self.json("https://canvas.nus.edu.sg/api/v1/courses").await
+    /// Get a list of courses of the current user that pass `filter`,
+    /// along with their enrollment terms.
+    pub async fn courses(&self, filter: &CourseFilter) -> Result<Vec<Course>> {
+        let mut query = vec![("include[]", "term")];
+        if !filter.all {
+            query.push(("enrollment_state", "active"));
+        }
+        let mut courses: Vec<Course> =
+            self.json_list(&self.endpoint("courses"), &query).await?;
+        let now = SystemTime::now();
+        courses.retain(|v| filter.matches(v, now));
+        Ok(courses)
     }
 
     /// Get the files of a folder.
-    pub async fn files(&self, folder_id: u32) -> Result<Value> {
+    pub async fn files(&self, folder_id: u32) -> Result<Vec<File>> {
         log::debug!("[API::FILES] {folder_id}");
-        let url = format!(
-            "https://canvas.nus.edu.sg/api/v1/folders/{folder_id}/files"
-        );
-        self.json(&url).await
+        let url = self.endpoint(&format!("folders/{folder_id}/files"));
+        self.json_list(&url, &[]).await
     }
 
     /// Get the folders of a particular course id.
-    pub async fn course_folders(&self, course_id: u32) -> Result<Value> {
-        let url = format!(
-            "https://canvas.nus.edu.sg/api/v1/courses/{course_id}/folders"
-        );
-        self.json(&url).await
+    pub async fn course_folders(&self, course_id: u32) -> Result<Vec<Folder>> {
+        let url = self.endpoint(&format!("courses/{course_id}/folders"));
+        self.json_list(&url, &[]).await
     }
 
-    /// Follows `url` to a file and downloads it to `filepath`.
-    pub async fn download(self, url: String, filepath: PathBuf) -> Result<()> {
+    /// Follows `url` to a file and downloads it to `filepath`. The
+    /// file is written to a temporary file next to `filepath` first,
+    /// and only moved into place once all `size` bytes are received.
+    pub async fn download(
+        self,
+        url: String,
+        filepath: PathBuf,
+        size: u64,
+        progress: &FileProgress,
+    ) -> Result<()> {
         log::info!("[API::DOWNLOAD] {filepath:?}");
-        if filepath.is_file() {
-            std::fs::remove_file(&filepath).ok();
-        }
-        if let Some(parent) = filepath.parent() {
-            if parent.as_os_str().is_empty() || !parent.is_dir() {
-                return Err(Error::DownloadNoParentDir(filepath.to_path_buf()));
+        let parent = filepath
+            .parent()
+            .filter(|v| !v.as_os_str().is_empty() && v.is_dir());
+        let (Some(parent), Some(filename)) = (parent, filepath.file_name())
+        else {
+            return Err(Error::DownloadNoParentDir(filepath));
+        };
+        let tmp_path =
+            parent.join(format!(".{}.part", filename.to_string_lossy()));
+        let mut attempt = 0;
+        let result = loop {
+            let result =
+                self.try_download(&url, &tmp_path, size, progress).await;
+            match result {
+                // connection dropped or stalled halfway through the body.
+                Err(ref e)
+                    if is_transient(e) && attempt < self.retry.retries =>
+                {
+                    let delay = backoff(&self.retry, attempt);
+                    attempt += 1;
+                    log::debug!(
+                        "[API::RETRY] {attempt}/{} in {delay:?}: {e}",
+                        self.retry.retries
+                    );
+                    progress.restart();
+                    tokio::time::sleep(delay).await;
+                }
+                result => break result,
             }
+        };
+        if let Err(e) = result {
+            std::fs::remove_file(&tmp_path).ok();
+            // report the file the user asked for, not the temporary one.
+            return Err(match e {
+                Error::DownloadSizeMismatch { size, received, .. } => {
+                    let path = filepath;
+                    Error::DownloadSizeMismatch { path, size, received }
+                }
+                e => e,
+            });
         }
-        let mut target = File::create(&filepath)?;
-        let response = reqwest::get(&url)
+        std::fs::rename(&tmp_path, &filepath)?;
+        Ok(())
+    }
+
+    /// Makes one attempt at downloading `url` to `filepath`.
+    async fn try_download(
+        &self,
+        url: &str,
+        filepath: &Path,
+        size: u64,
+        progress: &FileProgress,
+    ) -> Result<()> {
+        let req = self.client.get(url);
+        let response = self
+            .send(req)
             .await
+            .and_then(|v| v.error_for_status())
             .map_err(|e| Error::DownloadErr(url.to_string(), e))?;
-        let mut content = std::io::Cursor::new(response.bytes().await?);
-        std::io::copy(&mut content, &mut target)?;
-        Ok(())
+        write_file(response, url, filepath, size, progress).await
+    }
+}
+
+/// 5xx and 429 responses are worth retrying.
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Canvas refuses requests with a 403 once the quota runs out. A 403
+/// sent while the quota is low is taken to be one of those.
+fn is_throttled(res: &Response) -> bool {
+    res.status() == StatusCode::FORBIDDEN
+        && header_f64(res.headers(), RATE_LIMIT_REMAINING)
+            .is_some_and(|v| v < LOW_QUOTA)
+}
+
+fn header_f64(headers: &HeaderMap, name: &str) -> Option<f64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Download errors that are worth retrying.
+fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::ReqwestErr(e) => is_transient_err(e),
+        Error::ReadTimeout { .. } => true,
+        _ => false,
     }
 }
 
+/// Errors from dropped or failed connections are worth retrying.
+fn is_transient_err(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_request() || err.is_body()
+}
+
+/// Reads the `Retry-After` header, in seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    secs.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Delay before the retry after `attempt` failed attempts: the base
+/// delay doubled per attempt, with the upper half randomized so that
+/// concurrent requests do not retry in lockstep.
+fn backoff(retry: &Retry, attempt: u32) -> Duration {
+    let delay = Duration::from_millis(retry.delay_ms)
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_DELAY);
+    delay / 2 + delay.mul_f64(fastrand::f64() / 2.0)
+}
+
+#[test]
+fn test_backoff() {
+    let retry = Retry { retries: 5, delay_ms: 1000 };
+    for (attempt, secs) in [(0, 1), (1, 2), (2, 4), (10, 30)] {
+        let max = Duration::from_secs(secs);
+        let delay = backoff(&retry, attempt);
+        assert!(max / 2 <= delay && delay <= max, "{attempt}: {delay:?}");
+    }
+}
+
+#[test]
+fn test_quota_refill() {
+    let at = Instant::now() - Duration::from_secs(5);
+    let quota = Quota { remaining: 100.0, at };
+    assert!(quota.now() >= 100.0 + 5.0 * QUOTA_REFILL_RATE);
+}
+
+/// Streams the body of `response` to `filepath` chunk by chunk, and
+/// checks that it is exactly `size` bytes long.
+async fn write_file(
+    mut response: Response,
+    url: &str,
+    filepath: &Path,
+    size: u64,
+    progress: &FileProgress,
+) -> Result<()> {
+    progress.set_length(response.content_length());
+    let mut target = fs::File::create(filepath).await?;
+    let mut received = 0;
+    loop {
+        let chunk = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+            .await
+            .map_err(|_| Error::ReadTimeout { url: url.to_string() })??;
+        let Some(chunk) = chunk else { break };
+        target.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        progress.inc(chunk.len() as u64);
+    }
+    target.flush().await?;
+    if received != size {
+        let path = filepath.to_path_buf();
+        return Err(Error::DownloadSizeMismatch { path, size, received });
+    }
+    Ok(())
+}
+
+/// Resolves handles in batches of size `threads`, keeping the order
+/// of `handles` in the output.
+pub async fn resolve_ordered<I, F>(handles: I, threads: usize) -> Vec<F::Output>
+where
+    I: IntoIterator<Item = F>,
+    F: FutureExt,
+{
+    futures::stream::iter(handles)
+        .buffered(threads)
+        .collect::<Vec<F::Output>>()
+        .await
+}
+
 /// Resolves handles in batches of size `threads`
 pub async fn resolve<I, F>(handles: I, threads: usize) -> Vec<F::Output>
 where
@@ -1,21 +1,87 @@
 use crate::error::{Error, Result};
+use crate::filter::Filters;
+use crate::traits::ResolvePath;
 use crate::types::FolderMap;
 use crate::BINARY_NAME;
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 const CONFIG_NAME: &str = "config";
+const STATE_NAME: &str = "state.json";
+pub const DEFAULT_BASE_URL: &str = "https://canvas.nus.edu.sg";
+pub const DEFAULT_FOLDER_TEMPLATE: &str = "{course_code}";
 
 /// Serializeable version of the config.
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Config {
     access_token: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    base_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     base_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    folder_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Jobs::is_default")]
+    jobs: Jobs,
+    #[serde(default, skip_serializing_if = "Retry::is_default")]
+    retry: Retry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ca_certs: Vec<String>,
+    /// applied to every folder map, on top of its own filters.
+    #[serde(flatten)]
+    filters: Filters,
     #[serde(rename = "folders")]
     folder_maps: Vec<FolderMap>,
     #[serde(skip)]
-    config_path: String,
+    config_path: PathBuf,
+}
+
+/// Limits on how many things canvas-sync does at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Jobs {
+    /// files downloaded at a time.
+    pub downloads: usize,
+    /// folder maps synced at a time.
+    pub folders: usize,
+    /// file listings fetched at a time, per folder map.
+    pub listings: usize,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self { downloads: 4, folders: 5, listings: 10 }
+    }
+}
+
+impl Jobs {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// How requests that fail for transient reasons are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retry {
+    /// retries after the first attempt.
+    pub retries: u32,
+    /// delay before the first retry, doubled on every retry after.
+    pub delay_ms: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self { retries: 3, delay_ms: 500 }
+    }
+}
+
+impl Retry {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 impl Config {
@@ -24,29 +90,31 @@ impl Config {
     where
         P: AsRef<Path>,
     {
-        let cfg_path = Self::path()?;
+        let cfg_path = match &config_path {
+            Some(v) => v.as_ref().to_path_buf(),
+            None => Self::path()?,
+        };
         if let Ok(false) = cfg_path.try_exists() {
             println!(
                 "New config file created at\n'{}'\n",
                 cfg_path.to_string_lossy()
             )
         }
-        let mut config: Self = match config_path {
-            Some(v) => confy::load_path(v),
-            None => confy::load(BINARY_NAME, Some(CONFIG_NAME)),
-        }
-        .map_err(Error::ConfyErr)?;
+        let mut config: Self =
+            confy::load_path(&cfg_path).map_err(Error::ConfyErr)?;
 
         if check_token && config.access_token.is_empty() {
-            return Err(Error::EmptyToken);
+            let base_url = config.base_url().to_string();
+            return Err(Error::EmptyToken { base_url });
         }
 
-        // insert base paths into each folder map
+        // insert base paths and base url into each folder map
+        let base_url = config.base_url().to_string();
         config.folder_maps.iter_mut().for_each(|fm| {
-            fm.set(config.base_path.clone());
+            fm.set(config.base_path.clone(), &base_url);
         });
 
-        config.config_path = cfg_path.to_string_lossy().to_string();
+        config.config_path = cfg_path;
 
         Ok(config)
     }
@@ -56,14 +124,27 @@ impl Config {
             .map_err(|_| Error::UnableToGetConfigPath)
     }
 
+    /// Path of the sync state of this config, next to the config file.
+    /// Configs other than the default `config.yml` get their own state,
+    /// named after them, so that they never share records.
+    pub fn state_path(&self) -> PathBuf {
+        let stem = self.config_path.file_stem().unwrap_or_default();
+        let name = match stem.to_string_lossy() {
+            v if v == CONFIG_NAME => STATE_NAME.to_string(),
+            v => format!("{v}.{STATE_NAME}"),
+        };
+        self.config_path.with_file_name(name)
+    }
+
     pub fn set_token(&mut self, token: &str) {
         self.access_token = token.to_string();
     }
 
-    /// Saves the current state of canvas-sync config
+    /// Saves the current state of canvas-sync config to the file it
+    /// was loaded from.
     pub fn save(&self) -> Result<()> {
         log::info!("saving config: {self:?}");
-        confy::store(BINARY_NAME, Some(CONFIG_NAME), self).map_err(|e| e.into())
+        confy::store_path(&self.config_path, self).map_err(|e| e.into())
     }
 
     /// Get folder maps
@@ -71,8 +152,115 @@ impl Config {
         &self.folder_maps
     }
 
+    /// Start tracking a folder. Fails if its url is already tracked.
+    pub fn add_folder_map(&mut self, url: &str, path: &str) -> Result<()> {
+        let mut fm = FolderMap::new(url, path);
+        fm.set(self.base_path.clone(), self.base_url());
+        if self.folder_maps.iter().any(|v| v.url() == fm.url()) {
+            return Err(Error::AlreadyTracked(fm.url().to_string()));
+        }
+        self.folder_maps.push(fm);
+        Ok(())
+    }
+
+    /// Stop tracking a folder, given its number in the list of folder
+    /// maps (starting from 1), its url, or its local path.
+    pub fn remove_folder_map(&mut self, folder: &str) -> Result<FolderMap> {
+        let index = folder.parse::<usize>().ok();
+        let path = user_path(folder)?;
+        let found = self.folder_maps.iter().enumerate().position(|(i, v)| {
+            Some(i + 1) == index
+                || v.url() == folder
+                || user_path(v.local_dir()).is_ok_and(|v| v == path)
+        });
+        match found {
+            Some(i) => Ok(self.folder_maps.remove(i)),
+            None => Err(Error::NotTracked(folder.to_string())),
+        }
+    }
+
+    /// Get the template for the local paths of auto-tracked courses.
+    pub fn folder_template(&self) -> &str {
+        self.folder_template.as_deref().unwrap_or(DEFAULT_FOLDER_TEMPLATE)
+    }
+
+    /// Get the base path that relative folder map paths start from.
+    pub fn base_path(&self) -> Option<PathBuf> {
+        let path = PathBuf::from(self.base_path.as_ref()?);
+        Some(path.resolve().unwrap_or(path))
+    }
+
     /// Get access token
     pub fn access_token(&self) -> &str {
         &self.access_token
     }
+
+    /// Get concurrency limits, with each limit being at least 1.
+    pub fn jobs(&self) -> Jobs {
+        let Jobs { downloads, folders, listings } = self.jobs;
+        Jobs {
+            downloads: downloads.max(1),
+            folders: folders.max(1),
+            listings: listings.max(1),
+        }
+    }
+
+    /// Get retry settings
+    pub fn retry(&self) -> Retry {
+        self.retry
+    }
+
+    /// Get the url of the proxy that all requests go through, if any.
+    pub fn proxy(&self) -> Option<&str> {
+        self.proxy.as_deref()
+    }
+
+    /// Get the paths of PEM files with extra root certificates to
+    /// trust.
+    pub fn ca_certs(&self) -> Vec<PathBuf> {
+        let paths = self.ca_certs.iter().map(PathBuf::from);
+        paths.map(|v| v.resolve().unwrap_or(v)).collect()
+    }
+
+    /// Get the filters applied to every folder map.
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    /// Get the url of the Canvas instance, without a trailing slash.
+    pub fn base_url(&self) -> &str {
+        let url = self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        url.trim_end_matches('/')
+    }
+}
+
+/// Resolves a path given by the user on the command line: expands `~`
+/// and makes relative paths start from the current directory.
+pub fn user_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref().to_path_buf();
+    let path = std::env::current_dir()?.join(path.resolve().unwrap_or(path));
+    // `.` and `..` are resolved lexically, so that `./lec` and
+    // `../course/lec` both match the same folder.
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            v => resolved.push(v),
+        }
+    }
+    Ok(resolved)
+}
+
+#[test]
+fn test_state_path() {
+    let config = |path: &str| Config {
+        config_path: PathBuf::from(path),
+        ..Config::default()
+    };
+    let state_path = |path: &str| config(path).state_path();
+    assert_eq!(state_path("/a/config.yml"), Path::new("/a/state.json"));
+    assert_eq!(state_path("/a/cfg3.yml"), Path::new("/a/cfg3.state.json"));
 }
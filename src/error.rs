@@ -6,15 +6,28 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
-    EmptyToken,
-    InvalidToken,
-    CanvasError { msg: String, url: String },
+    EmptyToken { base_url: String },
+    InvalidToken { base_url: String },
+    Canvas { msg: String, url: String },
+    RateLimited { url: String },
+    UnexpectedResponse { url: String, err: serde_json::Error },
     UnableToGetConfigPath,
     Debug(String),
     DownloadNoParentDir(PathBuf),
     InvalidTrackingUrl(String),
     NoFoldersFoundInCourse { url: String },
+    CourseNotFound(String),
+    AlreadyTracked(String),
+    NotTracked(String),
+    NoBasePath,
     DownloadErr(String, reqwest::Error),
+    ReadTimeout { url: String },
+    DownloadSizeMismatch { path: PathBuf, size: u64, received: u64 },
+    SyncFailed(Vec<Error>),
+    InvalidProxy(reqwest::Error),
+    InvalidCaCert(PathBuf, String),
+    InvalidGlob(String, globset::Error),
+    InvalidSize(String),
 
     // wrapped errors
     ReqwestErr(reqwest::Error),
@@ -23,13 +36,13 @@ pub enum Error {
     ConfyErr(confy::ConfyError),
 }
 
-fn token_instructions(pre: &str) -> String {
+fn token_instructions(pre: &str, base_url: &str) -> String {
     format!(
         "\
 {pre}
 
 To obtain a token, go to your profile settings at
-https://canvas.nus.edu.sg/profile/settings
+{base_url}/profile/settings
 and create a new access token.
 
 Run `{BINARY_NAME} set-token <token>` to set the token,
@@ -43,16 +56,46 @@ fn display(err: &Error, f: &mut fmt::Formatter) -> fmt::Result {
     use Error::*;
     match err {
         Debug(msg) => p!("{msg}"),
-        CanvasError { msg, url } => p!("Canvas error: {msg}\nurl: {url}"),
-        EmptyToken => p!("{}", token_instructions("No token provided.")),
-        InvalidToken => {
-            p!("{}", token_instructions("Invalid access token."))
+        Canvas { msg, url } => p!("Canvas error: {msg}\nurl: {url}"),
+        RateLimited { url } => p!("Canvas rate limit exceeded.\nurl: {url}"),
+        UnexpectedResponse { url, err } => {
+            p!("Unexpected response from Canvas: {err}\nurl: {url}")
+        }
+        EmptyToken { base_url } => {
+            p!("{}", token_instructions("No token provided.", base_url))
+        }
+        InvalidToken { base_url } => {
+            p!("{}", token_instructions("Invalid access token.", base_url))
         }
         UnableToGetConfigPath => p!("Unable to get config path."),
         DownloadErr(url, err) => {
             p!("Failed to download from url {url}, {err}")
         }
+        ReadTimeout { url } => p!("Timed out waiting for data from {url}"),
+        DownloadSizeMismatch { path, size, received } => p!(
+            "Incomplete download: `{}` (expected {size} bytes, got {received}).",
+            path.to_string_lossy()
+        ),
+        SyncFailed(errors) => {
+            p!("\n{} sync task(s) failed:", errors.len())?;
+            for err in errors {
+                let err = err.to_string().replace('\n', "\n    ");
+                p!("\n  * {}", err.trim_end())?;
+            }
+            Ok(())
+        }
+        InvalidProxy(err) => p!("Invalid proxy url: {err}"),
+        InvalidCaCert(path, err) => p!(
+            "Unable to load CA certificate `{}`: {err}",
+            path.to_string_lossy()
+        ),
+        InvalidGlob(pattern, err) => p!("Invalid glob `{pattern}`: {err}"),
+        InvalidSize(v) => p!("Invalid size `{v}`, expected e.g. `200MB`."),
         InvalidTrackingUrl(v) => p!("Invalid url: {v}"),
+        CourseNotFound(v) => p!("No course found with id, code or name: {v}"),
+        AlreadyTracked(v) => p!("Folder is already tracked: {v}"),
+        NotTracked(v) => p!("No tracked folder matches: {v}"),
+        NoBasePath => p!("Set `base_path` in your config to track courses."),
         NoFoldersFoundInCourse { url } => {
             p!("No folders found in course: {url}")
         }
@@ -81,6 +124,48 @@ impl Error {
     pub fn of(msg: &str) -> Self {
         Error::Debug(msg.to_string())
     }
+
+    /// Process exit code for this error. Documented in the README.
+    ///
+    /// 1: anything else
+    /// 2: bad command line arguments (reported by clap)
+    /// 3: missing or invalid access token
+    /// 4: bad config
+    /// 5: network or Canvas errors
+    /// 6: some folders or downloads failed to sync, unless the token
+    ///    is why, which is still 3
+    pub fn exit_code(&self) -> i32 {
+        use Error::*;
+        match self {
+            EmptyToken { .. } | InvalidToken { .. } => 3,
+            SyncFailed(errors) if errors.iter().any(is_token_err) => 3,
+            UnableToGetConfigPath
+            | ConfyErr(_)
+            | InvalidTrackingUrl(_)
+            | NoFoldersFoundInCourse { .. }
+            | AlreadyTracked(_)
+            | NotTracked(_)
+            | NoBasePath
+            | DownloadNoParentDir(_)
+            | InvalidProxy(_)
+            | InvalidCaCert(..)
+            | InvalidGlob(..)
+            | InvalidSize(_) => 4,
+            Canvas { .. }
+            | RateLimited { .. }
+            | UnexpectedResponse { .. }
+            | DownloadErr(..)
+            | ReadTimeout { .. }
+            | DownloadSizeMismatch { .. }
+            | ReqwestErr(_) => 5,
+            SyncFailed(_) => 6,
+            Debug(_) | CourseNotFound(_) | SerdeJsonErr(_) | IoErr(_) => 1,
+        }
+    }
+}
+
+fn is_token_err(err: &Error) -> bool {
+    matches!(err, Error::EmptyToken { .. } | Error::InvalidToken { .. })
 }
 
 impl From<reqwest::Error> for Error {
@@ -106,3 +191,13 @@ impl From<confy::ConfyError> for Error {
         Self::ConfyErr(error)
     }
 }
+
+#[test]
+fn test_sync_failed_exit_code() {
+    let base_url = String::new();
+    let token = || Error::InvalidToken { base_url: base_url.clone() };
+    let other = || Error::NotTracked(String::new());
+    assert_eq!(Error::SyncFailed(vec![token(), token()]).exit_code(), 3);
+    assert_eq!(Error::SyncFailed(vec![other(), token()]).exit_code(), 3);
+    assert_eq!(Error::SyncFailed(vec![other()]).exit_code(), 6);
+}
@@ -0,0 +1,174 @@
+use crate::error::{Error, Result};
+use crate::string::{human_size, parse_size};
+use crate::types::File;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Which remote files get synced. Set for every folder in the config,
+/// and for each folder map on its own.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    /// only sync files matching one of these globs (if any are set).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// never sync files matching one of these globs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// skip files larger than this, e.g. `200MB`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<String>,
+    /// only download files with one of these MIME types (if any are
+    /// set), e.g. `application/pdf` or `text/*`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub content_types: Vec<String>,
+}
+
+/// Compiled `Filters` of a folder map, combined with the global ones.
+#[derive(Debug)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// the smallest of all `max_size`s, in bytes.
+    max_size: Option<u64>,
+    content_types: Option<GlobSet>,
+}
+
+impl FileFilter {
+    /// Combines the patterns of every one of `filters`.
+    pub fn new(filters: &[&Filters]) -> Result<Self> {
+        let include: Vec<_> = filters.iter().flat_map(|v| &v.include).collect();
+        let exclude: Vec<_> = filters.iter().flat_map(|v| &v.exclude).collect();
+        let content_types: Vec<_> =
+            filters.iter().flat_map(|v| &v.content_types).collect();
+        let mut max_size = None;
+        for v in filters.iter().filter_map(|v| v.max_size.as_ref()) {
+            let size = parse_size(v).ok_or(Error::InvalidSize(v.clone()))?;
+            max_size = Some(max_size.map_or(size, |m: u64| m.min(size)));
+        }
+        Ok(Self {
+            include: optional_glob_set(&include)?,
+            exclude: glob_set(&exclude)?,
+            max_size,
+            content_types: optional_glob_set(&content_types)?,
+        })
+    }
+
+    /// Whether a file is synced, given its path relative to the
+    /// tracked remote folder.
+    pub fn allows(&self, remote_path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|v| v.is_match(remote_path))
+            && !self.exclude.is_match(remote_path)
+    }
+
+    /// Why `file` is not downloaded, if it is too large or of a
+    /// content type that is not wanted.
+    pub fn skip_reason(&self, file: &File) -> Option<String> {
+        if let Some(max) = self.max_size.filter(|v| file.size > *v) {
+            let (size, max) = (human_size(file.size), human_size(max));
+            return Some(format!("{size}, over max_size of {max}"));
+        }
+        let content_type = match file.content_type.as_str() {
+            "" => "unknown",
+            v => v,
+        };
+        match &self.content_types {
+            Some(v) if !v.is_match(content_type) => {
+                Some(format!("content type {content_type}"))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `None` if there are no patterns to match.
+fn optional_glob_set(patterns: &[&String]) -> Result<Option<GlobSet>> {
+    match patterns.is_empty() {
+        true => Ok(None),
+        false => glob_set(patterns).map(Some),
+    }
+}
+
+/// Case-insensitive globs, in which `*` also matches across folders.
+fn glob_set(patterns: &[&String]) -> Result<GlobSet> {
+    let err = |pattern: &str| {
+        let pattern = pattern.to_string();
+        move |e| Error::InvalidGlob(pattern, e)
+    };
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern).case_insensitive(true).build();
+        set.add(glob.map_err(err(pattern))?);
+    }
+    let all: Vec<&str> = patterns.iter().map(|v| v.as_str()).collect();
+    set.build().map_err(err(&all.join(", ")))
+}
+
+#[test]
+fn test_file_filter() {
+    let global =
+        Filters { exclude: vec!["*.zip".into()], ..Filters::default() };
+    let fm = Filters {
+        include: vec!["*.pdf".into(), "Recordings/**".into()],
+        exclude: vec!["*.mp4".into()],
+        ..Filters::default()
+    };
+    let filter = FileFilter::new(&[&global, &fm]).unwrap();
+    assert!(filter.allows(Path::new("week1.pdf")));
+    assert!(filter.allows(Path::new("Lectures/week1.PDF")));
+    assert!(filter.allows(Path::new("Recordings/notes.txt")));
+    assert!(!filter.allows(Path::new("Recordings/week1.mp4")));
+    assert!(!filter.allows(Path::new("Tutorials/solutions.zip")));
+    assert!(!filter.allows(Path::new("notes.txt")));
+
+    let filter = FileFilter::new(&[&global]).unwrap();
+    assert!(filter.allows(Path::new("notes.txt")));
+    assert!(!filter.allows(Path::new("solutions.zip")));
+
+    let fm = Filters {
+        exclude: vec!["*-solutions.pdf".into()],
+        ..Filters::default()
+    };
+    let filter = FileFilter::new(&[&fm]).unwrap();
+    assert!(!filter.allows(Path::new("Tutorials/Tut1-solutions.pdf")));
+    assert!(filter.allows(Path::new("Tutorials/Tut1-questions.pdf")));
+}
+
+#[test]
+fn test_skip_reason() {
+    let global = Filters {
+        max_size: Some("200MB".into()),
+        content_types: vec!["application/pdf".into()],
+        ..Filters::default()
+    };
+    let fm = Filters {
+        max_size: Some("1GB".into()),
+        content_types: vec!["text/*".into()],
+        ..Filters::default()
+    };
+    let filter = FileFilter::new(&[&global, &fm]).unwrap();
+    let file = |size: u64, content_type: &str| File {
+        id: 1,
+        display_name: "file".to_string(),
+        size,
+        url: None,
+        updated_at: String::new(),
+        modified_at: None,
+        content_type: content_type.to_string(),
+    };
+    assert_eq!(filter.skip_reason(&file(1024, "application/pdf")), None);
+    assert_eq!(filter.skip_reason(&file(1024, "text/plain")), None);
+    assert_eq!(
+        filter.skip_reason(&file(300 * 1024 * 1024, "application/pdf")),
+        Some("300.0 MB, over max_size of 200.0 MB".to_string())
+    );
+    assert_eq!(
+        filter.skip_reason(&file(1024, "video/mp4")),
+        Some("content type video/mp4".to_string())
+    );
+    assert_eq!(
+        filter.skip_reason(&file(1024, "")),
+        Some("content type unknown".to_string())
+    );
+}
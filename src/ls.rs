@@ -0,0 +1,71 @@
+use crate::api::{self, Api};
+use crate::error::{Error, Result};
+use crate::string::human_size;
+use crate::types::{Course, Folder};
+
+/// Prints every course of the user with its id.
+pub fn display_courses(courses: &[Course]) {
+    if courses.is_empty() {
+        println!("No courses found.");
+        return;
+    }
+    for course in courses {
+        let code = course.course_code.as_deref().unwrap_or("-");
+        let name = course.name.as_deref().unwrap_or("(no access)");
+        println!("{:>8}  {code}  {name}", course.id);
+    }
+}
+
+/// Finds a course by its id, course code, or name.
+pub fn find_course<'a>(
+    courses: &'a [Course],
+    query: &str,
+) -> Result<&'a Course> {
+    let eq = |v: &Option<String>| {
+        v.as_deref().is_some_and(|v| v.eq_ignore_ascii_case(query))
+    };
+    let id = query.parse::<u32>().ok();
+    courses
+        .iter()
+        .find(|c| Some(c.id) == id || eq(&c.course_code) || eq(&c.name))
+        .ok_or_else(|| Error::CourseNotFound(query.to_string()))
+}
+
+/// Prints the folder tree of a course, with the number of files and
+/// their total size in each folder. Fetches at most `jobs` file
+/// listings at a time.
+pub async fn display_tree(
+    api: &Api,
+    base_url: &str,
+    course: &Course,
+    jobs: usize,
+) -> Result<()> {
+    let mut folders = api.course_folders(course.id).await?;
+    folders.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    let handles = folders.iter().map(|f| api.files(f.id));
+    let files = api::resolve_ordered(handles, jobs).await;
+
+    let name = course.name.as_deref().unwrap_or("(no access)");
+    println!("{name}");
+    println!(
+        "Track a folder with {base_url}/courses/{}/files/folder/<path>\n",
+        course.id
+    );
+    for (folder, files) in folders.iter().zip(files) {
+        let files = files?;
+        let size = human_size(files.iter().map(|v| v.size).sum());
+        let (depth, name) = tree_entry(folder);
+        let indent = "  ".repeat(depth);
+        println!("{indent}{name}/  ({} files, {size})", files.len());
+    }
+    Ok(())
+}
+
+/// Depth of a folder in its course, and its own name.
+fn tree_entry(folder: &Folder) -> (usize, &str) {
+    let path = folder.full_name.as_str();
+    match path.rsplit_once('/') {
+        Some((parent, name)) => (parent.matches('/').count() + 1, name),
+        None => (0, path),
+    }
+}
@@ -1,18 +1,28 @@
 mod api;
 mod config;
 mod error;
+mod filter;
+mod ls;
+mod output;
+mod progress;
+mod state;
+mod status;
 mod string;
 mod sync;
 mod traits;
 mod types;
 
 use api::Api;
-use config::Config;
+use config::{Config, Jobs};
 use error::{Error, Result};
+use output::{Event, Format, Output};
+use state::State;
+use string::{parse_url, render_template};
 use sync::Sync;
-use types::User;
+use types::CourseFilter;
 
 use clap::{Parser, Subcommand};
+use futures::FutureExt;
 
 use std::process::Command;
 
@@ -30,6 +40,14 @@ pub struct Args {
 
     #[arg(short, long)]
     version: bool,
+
+    /// Maximum number of files to download at a time
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
+
+    /// Output format of fetch, pull and the profile
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    format: Format,
 }
 
 #[derive(Subcommand, Debug)]
@@ -42,36 +60,92 @@ enum Commands {
         edit: bool,
     },
     Fetch,
-    Pull,
+    Pull {
+        /// Print what would be downloaded and where, without
+        /// downloading anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    Status,
+    /// Start tracking a Canvas folder in a local directory
+    Add {
+        url: String,
+        path: String,
+    },
+    /// Stop tracking a folder, by its number in `list`, url or path
+    Remove {
+        folder: String,
+    },
+    /// List tracked folders
+    List,
+    /// Track every course you are enrolled in, under base_path
+    Init {
+        /// Local path of each course, e.g. "{course_code}/{term}".
+        /// Also takes {name} and {id}
+        #[arg(short, long)]
+        template: Option<String>,
+        #[command(flatten)]
+        filter: CourseFilter,
+    },
+    /// List your courses, or the folder tree of one course
+    Ls {
+        /// Course id, course code or name
+        course: Option<String>,
+        #[command(flatten)]
+        filter: CourseFilter,
+    },
 }
 
 #[derive(Debug)]
 pub struct App {
     args: Args,
+    /// where results go, in the format asked for.
+    out: Output,
 }
 
 impl App {
     pub fn new(args: Option<Args>) -> Self {
         let args = args.unwrap_or_else(Args::parse);
         log::info!("{args:?}");
-        Self { args }
+        // only the profile, fetch and pull have machine-readable output.
+        let format = match &args.command {
+            None | Some(Commands::Fetch | Commands::Pull { .. }) => args.format,
+            _ => Format::Text,
+        };
+        Self { args, out: Output::new(format) }
+    }
+
+    /// Reports the error that stopped the command, if any, and prints
+    /// whatever output was held back.
+    pub fn finish(&self, outcome: &Result<()>) {
+        match outcome {
+            // each failed task was already reported on its own.
+            Err(Error::SyncFailed(_)) | Ok(_) => {}
+            Err(err) => {
+                let message = err.to_string();
+                self.out.emit(&Event::Error { folder_url: None, message });
+            }
+        }
+        self.out.finish();
     }
 
     /// Main entry point
     pub async fn run(&self) -> Result<()> {
         if self.args.version {
             println!("{BINARY_NAME} {}", VERSION.unwrap_or("unknown"));
-            return Ok(())
+            return Ok(());
         }
         let cfg_path = self.args.config_path.as_ref();
         let command = match &self.args.command {
             Some(v) => v,
             None => {
                 let config = Config::load(cfg_path, true)?;
-                let api = Api::new(config.access_token());
+                let api = Api::new(&config)?;
                 let profile = api.profile().await?;
-                let user = User::from(profile);
-                user.display();
+                match self.out.is_text() {
+                    true => profile.display(),
+                    false => self.out.emit(&Event::Profile(&profile)),
+                }
                 return Ok(());
             }
         };
@@ -99,32 +173,198 @@ New token set! Try running `{BINARY_NAME}` to verify it.
                 Command::new(editor).arg(Config::path()?).spawn()?.wait()?;
                 Ok(())
             }
-            C::Pull => self.sync(true).await,
-            C::Fetch => self.sync(false).await,
+            C::Pull { dry_run: false } => self.sync(true, false).await,
+            C::Pull { dry_run: true } => self.sync(false, true).await,
+            C::Fetch => self.sync(false, false).await,
+            C::Status => {
+                let config = Config::load(cfg_path, false)?;
+                let state = State::load(config.state_path())?;
+                status::display_status(&config, &state);
+                Ok(())
+            }
+            C::Add { url, path } => self.add(url, path).await,
+            C::Remove { folder } => {
+                let mut config = Config::load(cfg_path, false)?;
+                let fm = config.remove_folder_map(folder)?;
+                config.save()?;
+                println!("Stopped tracking {}", fm.url());
+                Ok(())
+            }
+            C::List => {
+                let config = Config::load(cfg_path, false)?;
+                for (i, fm) in config.folder_maps().iter().enumerate() {
+                    let local_dir = fm.local_dir();
+                    println!("{}. {}", i + 1, fm.url());
+                    println!("   -> {}", local_dir.to_string_lossy());
+                }
+                Ok(())
+            }
+            C::Init { template, filter } => {
+                self.init(template.as_deref(), filter).await
+            }
+            C::Ls { course, filter } => {
+                let config = Config::load(cfg_path, true)?;
+                let api = Api::new(&config)?;
+                let courses = api.courses(filter).await?;
+                let Some(query) = course else {
+                    ls::display_courses(&courses);
+                    return Ok(());
+                };
+                let course = ls::find_course(&courses, query)?;
+                let jobs = self.jobs(&config).listings;
+                ls::display_tree(&api, config.base_url(), course, jobs).await
+            }
         }
     }
 
-    /// Runs a full sync on every folder listed. Only downloads
-    /// files of `download` is set to true.
-    async fn sync(&self, download: bool) -> Result<()> {
+    /// Starts tracking the folder at `url` in the local directory
+    /// `path`, after checking that the folder exists on Canvas.
+    async fn add(&self, url: &str, path: &str) -> Result<()> {
         let cfg_path = self.args.config_path.as_ref();
-        let config = Config::load(cfg_path, true)?;
-        let api = Api::new(config.access_token());
-        let syncers = config
+        let mut config = Config::load(cfg_path, true)?;
+        let (course_id, remote_dir) = parse_url(url, config.base_url())?;
+        let api = Api::new(&config)?;
+        let folders = api.course_folders(course_id).await?;
+        if !folders.iter().any(|v| v.remote_dir(&remote_dir).is_some()) {
+            let url = url.to_string();
+            return Err(Error::NoFoldersFoundInCourse { url });
+        }
+
+        // store paths inside `base_path` relative to it.
+        let path = config::user_path(path)?;
+        let path = match config.base_path() {
+            Some(base) => path.strip_prefix(base).unwrap_or(&path),
+            None => &path,
+        };
+        config.add_folder_map(url, &path.to_string_lossy())?;
+        let fm = config.folder_maps().last().expect("just added");
+        if !fm.parent_exists() {
+            return Err(Error::DownloadNoParentDir(fm.local_dir()));
+        }
+        let local_dir = fm.local_dir();
+        config.save()?;
+        println!("Now tracking {url}\n  -> {}", local_dir.to_string_lossy());
+        Ok(())
+    }
+
+    /// Tracks the files of every course that passes `filter`, at a
+    /// path under `base_path` given by `template`. Courses that are
+    /// already tracked are left alone.
+    async fn init(
+        &self,
+        template: Option<&str>,
+        filter: &CourseFilter,
+    ) -> Result<()> {
+        let cfg_path = self.args.config_path.as_ref();
+        let mut config = Config::load(cfg_path, true)?;
+        let base_path = config.base_path().ok_or(Error::NoBasePath)?;
+        let template = template.unwrap_or(config.folder_template()).to_string();
+        let api = Api::new(&config)?;
+        let tracked: Vec<u32> = config
             .folder_maps()
             .iter()
-            .map(|fm| Sync::new(&api, fm, download))
-            .collect::<Result<Vec<_>>>()?;
-        let handles =
-            syncers.into_iter().map(|v| v.get_updates()).collect::<Vec<_>>();
-        println!("Syncing {} folders...", handles.len());
-
-        // sync at most 5 folders at a time.
-        let loloupdates: Vec<Result<Vec<_>>> = api::resolve(handles, 5).await;
-        let updates = untangle(loloupdates)?;
-        Sync::run(&api, updates, download).await?;
+            .filter_map(|v| v.course_id().ok())
+            .collect();
+        let mut added = 0;
+        for course in api.courses(filter).await? {
+            let term = course.term.as_ref();
+            let Some(name) = course.name.as_deref() else { continue };
+            if tracked.contains(&course.id) {
+                continue;
+            }
+            let id = course.id.to_string();
+            let values = [
+                ("course_code", course.course_code.as_deref().unwrap_or(&id)),
+                ("name", name),
+                ("id", &id),
+                ("term", term.map_or("", |v| &v.name)),
+            ];
+            let path = render_template(&template, &values);
+            let url = format!("{}/courses/{id}/files", config.base_url());
+            let local_dir = base_path.join(&path);
+            std::fs::create_dir_all(&local_dir)?;
+            config.add_folder_map(&url, &path)?;
+            println!("+ {name}\n  -> {}", local_dir.to_string_lossy());
+            added += 1;
+        }
+        if added == 0 {
+            println!("No new courses to track.");
+            return Ok(());
+        }
+        config.save()?;
+        println!("Now tracking {added} more course(s).");
         Ok(())
     }
+
+    /// Concurrency limits from the config, with `--jobs` applied.
+    fn jobs(&self, config: &Config) -> Jobs {
+        let mut jobs = config.jobs();
+        if let Some(downloads) = self.args.jobs {
+            jobs.downloads = downloads.max(1);
+        }
+        jobs
+    }
+
+    /// Runs a full sync on every folder listed. Only downloads
+    /// files of `download` is set to true, and only prints the plan
+    /// if `dry_run` is. A folder or a download that fails does not
+    /// stop the others; all failures are reported together at the
+    /// end.
+    async fn sync(&self, download: bool, dry_run: bool) -> Result<()> {
+        let cfg_path = self.args.config_path.as_ref();
+        let config = Config::load(cfg_path, true)?;
+        let api = Api::new(&config)?;
+        let mut state = State::load(config.state_path())?;
+        let jobs = self.jobs(&config);
+        // errors are kept with the url of the folder map they belong to.
+        let (mut syncers, mut errors) = (vec![], vec![]);
+        for fm in config.folder_maps() {
+            let filters = config.filters();
+            match Sync::new(&api, fm, &state, filters, jobs.listings, download)
+            {
+                Ok(v) => syncers.push(v),
+                Err(e) => errors.push((fm.url().to_string(), e)),
+            }
+        }
+        let handles = syncers.into_iter().map(|v| {
+            let url = v.url().to_string();
+            v.get_updates().map(move |updates| (url, updates))
+        });
+        let out = &self.out;
+        if out.is_text() {
+            println!("Syncing {} folders...", config.folder_maps().len());
+        }
+
+        let loloupdates = api::resolve(handles, jobs.folders).await;
+        let updates = untangle(loloupdates, &mut errors);
+        // scripts get the same updates from a dry run as from a fetch.
+        if dry_run && out.is_text() {
+            sync::display_plan(&updates);
+        } else {
+            let (state, jobs) = (&mut state, jobs.downloads);
+            let run = Sync::run(&api, updates, state, jobs, download, out);
+            errors.extend(run.await);
+        }
+        if download {
+            for fm in config.folder_maps() {
+                let fm_errors = errors.iter().filter(|v| v.0 == fm.url());
+                let fm_errors = fm_errors.map(|v| v.1.to_string()).collect();
+                state.record_sync(fm.url(), fm_errors);
+            }
+            state.save()?;
+        }
+        for (url, err) in &errors {
+            let message = err.to_string();
+            out.emit(&Event::Error { folder_url: Some(url), message });
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => {
+                let errors = errors.into_iter().map(|v| v.1).collect();
+                Err(Error::SyncFailed(errors))
+            }
+        }
+    }
 }
 
 #[tokio::main]
@@ -132,12 +372,25 @@ async fn main() {
     env_logger::init();
     let app = App::new(None);
     let outcome = app.run().await;
+    app.finish(&outcome);
     if let Err(err) = outcome {
-        eprintln!("{err}")
+        eprintln!("{err}");
+        std::process::exit(err.exit_code());
     }
 }
 
-fn untangle<T>(tasks: Vec<Result<Vec<T>>>) -> Result<Vec<T>> {
-    let tasks: Result<Vec<Vec<T>>> = tasks.into_iter().collect();
-    Ok(tasks?.into_iter().flatten().collect())
+/// Flattens the successful tasks, and moves the errors of the failed
+/// ones into `errors`, keeping the key of each task.
+fn untangle<K, T>(
+    tasks: Vec<(K, Result<Vec<T>>)>,
+    errors: &mut Vec<(K, Error)>,
+) -> Vec<T> {
+    let mut values = vec![];
+    for (key, task) in tasks {
+        match task {
+            Ok(v) => values.extend(v),
+            Err(e) => errors.push((key, e)),
+        }
+    }
+    values
 }
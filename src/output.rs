@@ -0,0 +1,107 @@
+use crate::types::{Profile, Update};
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+
+/// How results are printed. Documented in the README.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// for humans
+    #[default]
+    Text,
+    /// one array of all events, printed at the end
+    Json,
+    /// one event per line, printed as soon as it is known
+    Ndjson,
+}
+
+/// One thing worth reporting to scripts, tagged by `type`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Profile(&'a Profile),
+    Update {
+        /// absent if the course could not be fetched.
+        course_name: Option<&'a str>,
+        #[serde(flatten)]
+        update: &'a Update,
+    },
+    Error {
+        /// url of the folder map that failed, if it was just one.
+        folder_url: Option<&'a str>,
+        message: String,
+    },
+}
+
+/// Where events go in the `json` and `ndjson` formats. In the `text`
+/// format, callers print for humans themselves, and events are dropped.
+#[derive(Debug)]
+pub struct Output {
+    format: Format,
+    /// events held back until `finish`, in the `json` format.
+    events: RefCell<Vec<Value>>,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Self {
+        Self { format, events: RefCell::new(vec![]) }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text
+    }
+
+    pub fn emit(&self, event: &Event) {
+        if self.is_text() {
+            return;
+        }
+        let value = match serde_json::to_value(event) {
+            Ok(v) => v,
+            Err(e) => return log::warn!("[OUTPUT] unable to serialize: {e}"),
+        };
+        match self.format {
+            Format::Ndjson => println!("{value}"),
+            _ => self.events.borrow_mut().push(value),
+        }
+    }
+
+    /// Prints the events held back, in the `json` format.
+    pub fn finish(&self) {
+        if self.format == Format::Json {
+            let events = Value::Array(self.events.take());
+            println!("{events:#}");
+        }
+    }
+}
+
+#[test]
+fn test_update_event() {
+    use crate::state::FileState;
+    use crate::types::UpdateKind;
+    let file = FileState {
+        remote_path: "Week 1/notes.pdf".into(),
+        size: 1024,
+        updated_at: "2023-01-01T00:00:00Z".to_string(),
+        local_path: "/home/me/MA2101/Week 1/notes.pdf".into(),
+    };
+    let url = "https://canvas.nus.edu.sg/courses/1/files";
+    let update = Update::new(1, url, 2, UpdateKind::New, file);
+    let event =
+        Event::Update { course_name: Some("Analysis"), update: &update };
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        serde_json::json!({
+            "type": "update",
+            "course_name": "Analysis",
+            "course_id": 1,
+            "folder_url": url,
+            "file_id": 2,
+            "kind": "new",
+            "remote_path": "Week 1/notes.pdf",
+            "size": 1024,
+            "updated_at": "2023-01-01T00:00:00Z",
+            "local_path": "/home/me/MA2101/Week 1/notes.pdf",
+            "skip_reason": null,
+        })
+    );
+}
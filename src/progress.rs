@@ -0,0 +1,140 @@
+use crate::string::human_size;
+use indicatif::{
+    MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const TOTAL_TEMPLATE: &str = "{msg:>12} [{elapsed_precise}] {wide_bar} \
+    {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}";
+const FILE_TEMPLATE: &str =
+    "{msg:40!} {bar:20} {bytes}/{total_bytes} {binary_bytes_per_sec}";
+
+/// Live progress of the downloads of a pull: one bar per file being
+/// downloaded, and one for all of them. When not on a terminal, one
+/// line is printed per completed file instead.
+pub struct Progress {
+    bars: MultiProgress,
+    total: ProgressBar,
+    files: u64,
+    /// files finished, whether downloaded or failed.
+    done: Arc<AtomicU64>,
+    /// draw bars, instead of printing lines.
+    tty: bool,
+    hidden: bool,
+}
+
+impl Progress {
+    /// Progress of downloading `files` files of `bytes` bytes in
+    /// total. Shows nothing at all if `hidden`, or if there is nothing
+    /// to download.
+    pub fn new(files: usize, bytes: u64, hidden: bool) -> Self {
+        let hidden = hidden || files == 0;
+        let tty = !hidden && std::io::stderr().is_terminal();
+        let bars = match tty {
+            true => MultiProgress::new(),
+            false => {
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+            }
+        };
+        let style = ProgressStyle::with_template(TOTAL_TEMPLATE)
+            .expect("valid template");
+        let total = bars.add(ProgressBar::new(bytes).with_style(style));
+        let files = files as u64;
+        total.set_message(format!("0/{files} files"));
+        let done = Arc::new(AtomicU64::new(0));
+        Self { bars, total, files, done, tty, hidden }
+    }
+
+    /// Starts the progress of one file, of `size` bytes according to
+    /// Canvas.
+    pub fn file(&self, name: &str, size: u64) -> FileProgress {
+        let style = ProgressStyle::with_template(FILE_TEMPLATE)
+            .expect("valid template");
+        let bar = match self.tty {
+            true => {
+                self.bars.insert_before(&self.total, ProgressBar::new(size))
+            }
+            false => ProgressBar::hidden(),
+        };
+        bar.set_style(style);
+        bar.set_message(name.to_string());
+        FileProgress {
+            bar,
+            total: self.total.clone(),
+            files: self.files,
+            done: self.done.clone(),
+            name: name.to_string(),
+            size,
+            tty: self.tty,
+            hidden: self.hidden,
+        }
+    }
+
+    /// Removes the bars, leaving the terminal as it was.
+    pub fn finish(&self) {
+        self.total.finish_and_clear();
+        self.bars.clear().ok();
+    }
+}
+
+/// Progress of downloading one file.
+pub struct FileProgress {
+    bar: ProgressBar,
+    total: ProgressBar,
+    files: u64,
+    done: Arc<AtomicU64>,
+    name: String,
+    /// size planned for in the overall progress.
+    size: u64,
+    tty: bool,
+    hidden: bool,
+}
+
+impl FileProgress {
+    /// Sets the size of the file from the `Content-Length` of the
+    /// response, if there is one.
+    pub fn set_length(&self, len: Option<u64>) {
+        self.bar.set_length(len.unwrap_or(self.size));
+    }
+
+    /// Records `bytes` more bytes received.
+    pub fn inc(&self, bytes: u64) {
+        self.bar.inc(bytes);
+        self.total.inc(bytes);
+    }
+
+    /// Starts over, before retrying the download.
+    pub fn restart(&self) {
+        self.take_back(self.bar.position());
+        self.bar.reset();
+    }
+
+    /// Marks the file as downloaded.
+    pub fn finish(self) {
+        self.end();
+        if !self.tty && !self.hidden {
+            println!("  downloaded {} ({})", self.name, human_size(self.size));
+        }
+    }
+
+    /// Marks the file as failed, leaving it out of the overall progress.
+    pub fn fail(self) {
+        self.take_back(self.bar.position());
+        let total = self.total.length().unwrap_or(0);
+        self.total.set_length(total.saturating_sub(self.size));
+        self.end();
+    }
+
+    fn end(&self) {
+        self.bar.finish_and_clear();
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.total.set_message(format!("{done}/{} files", self.files));
+    }
+
+    fn take_back(&self, bytes: u64) {
+        let position = self.total.position();
+        self.total.set_position(position.saturating_sub(bytes));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Record of one file downloaded by canvas-sync.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    /// path relative to the tracked remote folder.
+    pub remote_path: PathBuf,
+    pub size: u64,
+    /// `modified_at` (or `updated_at`) of the file on Canvas.
+    pub updated_at: String,
+    /// where the file was downloaded to.
+    pub local_path: PathBuf,
+}
+
+/// Files downloaded for one folder map.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FolderState {
+    /// keyed by Canvas file id.
+    files: BTreeMap<u32, FileState>,
+    /// when the folder map was last pulled, in RFC 3339.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_sync: Option<String>,
+    /// errors from the last pull.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+impl FolderState {
+    pub fn file(&self, file_id: u32) -> Option<&FileState> {
+        self.files.get(&file_id)
+    }
+
+    pub fn last_sync(&self) -> Option<&str> {
+        self.last_sync.as_deref()
+    }
+
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+}
+
+/// Serializable record of everything canvas-sync has downloaded,
+/// stored next to the config file.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// keyed by folder map url.
+    folders: BTreeMap<String, FolderState>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl State {
+    /// Loads the sync state at `path` (see `Config::state_path`), or
+    /// an empty one if nothing has been synced yet.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut state: Self = match path.is_file() {
+            true => serde_json::from_slice(&std::fs::read(&path)?)?,
+            false => Self::default(),
+        };
+        state.path = path;
+        Ok(state)
+    }
+
+    /// Saves the sync state to the file it was loaded from. Writes to
+    /// a temporary file first so that an interrupted save never leaves
+    /// a corrupted state behind.
+    pub fn save(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Get the state of a folder map by its url.
+    pub fn folder(&self, url: &str) -> Option<&FolderState> {
+        self.folders.get(url)
+    }
+
+    /// Record that a folder map was just pulled, with the errors that
+    /// came up (if any).
+    pub fn record_sync(&mut self, url: &str, errors: Vec<String>) {
+        let folder = self.folders.entry(url.to_string()).or_default();
+        let now = humantime::format_rfc3339_seconds(SystemTime::now());
+        folder.last_sync = Some(now.to_string());
+        folder.errors = errors;
+    }
+
+    /// Record a successful download.
+    pub fn insert(&mut self, url: &str, file_id: u32, file: FileState) {
+        let folder = self.folders.entry(url.to_string()).or_default();
+        folder.files.insert(file_id, file);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::config::Config;
+use crate::state::State;
+use std::path::Path;
+
+/// Prints the sync state of every folder map, using only what is
+/// stored locally.
+pub fn display_status(config: &Config, state: &State) {
+    if config.folder_maps().is_empty() {
+        println!("No folders tracked. Add some to your config.");
+        return;
+    }
+    for fm in config.folder_maps() {
+        let local_dir = fm.local_dir();
+        let folder = state.folder(fm.url());
+        println!("{}", local_dir.to_string_lossy());
+        println!("  url:       {}", fm.url());
+        match local_dir.is_dir() {
+            true => println!("  files:     {}", count_files(&local_dir)),
+            false => println!("  files:     (directory does not exist)"),
+        }
+        match folder.and_then(|v| v.last_sync()) {
+            Some(v) => println!("  last sync: {v}"),
+            None => println!("  last sync: never"),
+        }
+        let errors = folder.map_or(&[][..], |v| v.errors());
+        match errors.len() {
+            0 => println!("  errors:    none"),
+            n => {
+                println!("  errors:    {n}");
+                for err in errors {
+                    println!("    * {}", err.replace('\n', "\n      "));
+                }
+            }
+        }
+    }
+}
+
+/// Counts the files in `dir` and all its subdirectories.
+fn count_files(dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    entries
+        .filter_map(|v| v.ok())
+        .map(|v| match v.file_type() {
+            Ok(t) if t.is_dir() => count_files(&v.path()),
+            Ok(t) if t.is_file() => 1,
+            _ => 0,
+        })
+        .sum()
+}
@@ -1,22 +1,24 @@
 use crate::error::{Error, Result};
 
 /// Parses a url in a url-path config pair to extract course id and
-/// full folder name.
+/// full folder name. `base_url` is the url of the Canvas instance
+/// that the tracked folder lives on.
 ///
 /// Example input:
 /// https://canvas.nus.edu.sg/courses/38518/files/folder/Lectures/Java%20Intro
 ///
 /// Expected output:
 /// (38518, "Lectures/Java Intro")
-pub fn parse_url(mut url: &str) -> Result<(u32, String)> {
+pub fn parse_url(mut url: &str, base_url: &str) -> Result<(u32, String)> {
     let err = || Error::InvalidTrackingUrl(url.to_string());
-    if url.starts_with("https://") {
-        url = &url[8..];
+    url = strip_scheme(url);
+    if let Some(v) = url.strip_prefix(strip_scheme(base_url)) {
+        url = v;
     }
-    if url.starts_with("canvas.nus.edu.sg/courses/") {
-        url = &url[26..];
+    if url.starts_with("/courses/") {
+        url = &url[9..];
     }
-    let (id, mut folder) = url.split_once("/").ok_or(err())?;
+    let (id, mut folder) = url.split_once('/').ok_or(err())?;
     let id = id.parse::<u32>().map_err(|_| err())?;
     if folder.starts_with("files") {
         folder = &folder[5..];
@@ -24,7 +26,7 @@ pub fn parse_url(mut url: &str) -> Result<(u32, String)> {
     if folder.starts_with("/folder") {
         folder = &folder[7..];
     }
-    if folder.starts_with("/") {
+    if folder.starts_with('/') {
         folder = &folder[1..];
     }
     if let Ok(decoded) = urlencoding::decode(folder) {
@@ -33,20 +35,161 @@ pub fn parse_url(mut url: &str) -> Result<(u32, String)> {
     Ok((id, folder.to_string()))
 }
 
+/// Removes the `https://` or `http://` prefix of a url, if any.
+fn strip_scheme(url: &str) -> &str {
+    let url = url.strip_prefix("https://").unwrap_or(url);
+    url.strip_prefix("http://").unwrap_or(url)
+}
+
 #[test]
 fn test_parse_url() -> Result<()> {
+    let base_url = "https://canvas.nus.edu.sg";
     let (id, path) =
-        parse_url("https://canvas.nus.edu.sg/courses/36732/files")?;
+        parse_url("https://canvas.nus.edu.sg/courses/36732/files", base_url)?;
     assert_eq!(id, 36732);
     assert_eq!(path, "");
     let (id, path) = parse_url(
         "https://canvas.nus.edu.sg/courses/36732/files/folder/Lecture%20Notes",
+        base_url,
     )?;
     assert_eq!(id, 36732);
     assert_eq!(path, "Lecture Notes");
     Ok(())
 }
 
+#[test]
+fn test_parse_url_other_instance() -> Result<()> {
+    let base_url = "https://canvas.instructure.com";
+    let (id, path) = parse_url(
+        "https://canvas.instructure.com/courses/123/files/folder/Tutorials",
+        base_url,
+    )?;
+    assert_eq!(id, 123);
+    assert_eq!(path, "Tutorials");
+    let url = "https://canvas.nus.edu.sg/courses/36732/files";
+    assert!(parse_url(url, base_url).is_err());
+    Ok(())
+}
+
+/// Extracts the url marked `rel="next"` from a `Link` header sent by
+/// Canvas for paginated requests.
+///
+/// Example input:
+/// <https://x/api/v1/courses?page=2>; rel="next",<https://x/api/v1/courses?page=1>; rel="first"
+///
+/// Expected output:
+/// https://x/api/v1/courses?page=2
+pub fn next_link(header: &str) -> Option<&str> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        let is_next = params.split(';').any(|v| v.trim() == "rel=\"next\"");
+        let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+        is_next.then_some(url)
+    })
+}
+
+#[test]
+fn test_next_link() {
+    let header = "\
+<https://x/api/v1/courses?page=1&per_page=10>; rel=\"current\",\
+<https://x/api/v1/courses?page=2&per_page=10>; rel=\"next\",\
+<https://x/api/v1/courses?page=1&per_page=10>; rel=\"first\"";
+    assert_eq!(
+        next_link(header),
+        Some("https://x/api/v1/courses?page=2&per_page=10")
+    );
+    let header = "<https://x/api/v1/courses?page=1>; rel=\"last\"";
+    assert_eq!(next_link(header), None);
+}
+
+/// Fills in `{key}` placeholders in `template` with their values.
+/// Slashes in values are replaced so that each value stays within one
+/// path component.
+///
+/// Example input:
+/// "{course_code}/{term}", [("course_code", "CS1010S"), ("term", "2023/24 Sem 1")]
+///
+/// Expected output:
+/// CS1010S/2023_24 Sem 1
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    values.iter().fold(template.to_string(), |acc, (key, value)| {
+        acc.replace(&format!("{{{key}}}"), &value.replace('/', "_"))
+    })
+}
+
+#[test]
+fn test_render_template() {
+    let values = [("course_code", "CS1010S"), ("term", "2023/24 Sem 1")];
+    assert_eq!(
+        render_template("{course_code}/{term}", &values),
+        "CS1010S/2023_24 Sem 1"
+    );
+    assert_eq!(render_template("{course_code}/{id}", &values), "CS1010S/{id}");
+}
+
+/// Formats a number of bytes for humans, in powers of 1024.
+///
+/// Example input:
+/// 1536
+///
+/// Expected output:
+/// 1.5 KB
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+#[test]
+fn test_human_size() {
+    assert_eq!(human_size(0), "0 B");
+    assert_eq!(human_size(1023), "1023 B");
+    assert_eq!(human_size(1536), "1.5 KB");
+    assert_eq!(human_size(200 * 1024 * 1024), "200.0 MB");
+}
+
+/// Parses a number of bytes written for humans, in powers of 1024.
+/// The unit is optional and ignores case.
+///
+/// Example input:
+/// 1.5 KB
+///
+/// Expected output:
+/// 1536
+pub fn parse_size(v: &str) -> Option<u64> {
+    let v = v.trim();
+    let end = v.find(|c: char| !c.is_ascii_digit() && c != '.');
+    let (number, unit) = v.split_at(end.unwrap_or(v.len()));
+    let number: f64 = number.parse().ok()?;
+    let power = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" => 1,
+        "M" | "MB" => 2,
+        "G" | "GB" => 3,
+        "T" | "TB" => 4,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(power)) as u64)
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("500"), Some(500));
+    assert_eq!(parse_size("1.5 KB"), Some(1536));
+    assert_eq!(parse_size("200MB"), Some(200 * 1024 * 1024));
+    assert_eq!(parse_size("1gb"), Some(1024 * 1024 * 1024));
+    assert_eq!(parse_size("MB"), None);
+    assert_eq!(parse_size("200 MiB"), None);
+}
+
 /// Normalize filename by replacing '+' and '-' with '_', and then
 /// replacing all "__" with '_'
 pub fn normalize_filename(v: &str) -> String {
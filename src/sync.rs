@@ -1,21 +1,31 @@
-use crate::api::Api;
+use crate::api::{self, Api};
 use crate::error::{Error, Result};
-use crate::traits::*;
-use crate::types::{FolderMap, Update};
+use crate::filter::{FileFilter, Filters};
+use crate::output::{Event, Output};
+use crate::progress::{FileProgress, Progress};
+use crate::state::{FileState, FolderState, State};
+use crate::string::human_size;
+use crate::types::{CourseFilter, File, FolderMap, Update, UpdateKind};
 
-use futures::future::try_join_all;
+use filetime::FileTime;
 use futures::{FutureExt, StreamExt};
-use serde_json::Value;
 
 use std::collections::HashMap;
 use std::mem;
 use std::path::Path;
+use std::time::SystemTime;
 
 pub struct Sync<'a> {
     api: &'a Api,
     fm: &'a FolderMap,
+    /// what was downloaded for this folder map on previous syncs.
+    state: Option<&'a FolderState>,
     course_id: u32,
     remote_dir: String,
+    /// which files of the folder are synced.
+    filter: FileFilter,
+    /// file listings fetched at a time.
+    listings: usize,
     download: bool,
 }
 
@@ -23,6 +33,9 @@ impl<'a> Sync<'a> {
     pub fn new(
         api: &'a Api,
         fm: &'a FolderMap,
+        state: &'a State,
+        filters: &Filters,
+        listings: usize,
         download: bool,
     ) -> Result<Self> {
         if !fm.parent_exists() {
@@ -30,47 +43,69 @@ impl<'a> Sync<'a> {
         }
         let course_id = fm.course_id()?;
         let remote_dir = fm.remote_dir()?;
-        Ok(Self { api, fm, download, course_id, remote_dir })
+        let state = state.folder(fm.url());
+        let filter = FileFilter::new(&[filters, fm.filters()])?;
+        Ok(Self {
+            api,
+            fm,
+            state,
+            listings,
+            download,
+            course_id,
+            remote_dir,
+            filter,
+        })
+    }
+
+    /// Url of the folder map being synced.
+    pub fn url(&self) -> &str {
+        self.fm.url()
     }
 
     /// Get updates contained within a folder
     fn get_folder_updates(
         &self,
-        folder_files: Result<(String, Value)>,
+        folder_files: Result<(String, Vec<File>)>,
         local_dir: &Path,
     ) -> Result<Vec<Update>> {
-        match folder_files {
-            Err(e) => Err(e),
-            Ok((remote_path, files)) => {
-                let files =
-                    files.as_array().ok_or(Error::NoFoldersFoundInCourse {
-                        url: self.fm.url().to_string(),
-                    })?;
-                let remote_path = Path::new(&remote_path);
-                let updates: Vec<Update> = files
-                    .into_iter()
-                    .filter_map(|f| {
-                        let url = f["url"].as_str()?.to_string();
-                        let filename = f.to_normalized_filename()?;
-                        let final_dir = local_dir.join(&remote_path);
-                        let target_file = final_dir.join(&filename);
-                        let has = target_file.is_file();
-                        let get = self.download && !has;
-                        if get {
-                            std::fs::create_dir_all(&final_dir).ok();
-                        }
-                        (!has).then(|| {
-                            Update::new(
-                                self.course_id,
-                                remote_path.join(&filename),
-                                get.then(|| (url, target_file)),
-                            )
-                        })
-                    })
-                    .collect();
-                Ok(updates)
-            }
-        }
+        let (remote_path, files) = folder_files?;
+        let remote_path = Path::new(&remote_path);
+        let updates: Vec<Update> = files
+            .iter()
+            .filter_map(|f| {
+                let Some(url) = f.download_url() else {
+                    log::debug!("[SYNC] no download url: {}", f.display_name);
+                    return None;
+                };
+                // filters see the name as it is on Canvas.
+                if !self.filter.allows(&remote_path.join(&f.display_name)) {
+                    log::debug!("[SYNC] filtered out: {}", f.display_name);
+                    return None;
+                }
+                let filename = f.normalized_filename();
+                let file = FileState {
+                    remote_path: remote_path.join(&filename),
+                    size: f.size,
+                    updated_at: f.last_modified().to_string(),
+                    local_path: local_dir.join(remote_path).join(&filename),
+                };
+                let prev = self.state.and_then(|v| v.file(f.id));
+                let kind = compare(&file, prev, f.last_modified_time())?;
+                let mut update = Update::new(
+                    self.course_id,
+                    self.fm.url(),
+                    f.id,
+                    kind,
+                    file,
+                );
+                update.skip = self.filter.skip_reason(f);
+                if self.download && update.skip.is_none() {
+                    update.download = Some(url.to_string());
+                }
+                Some(update)
+            })
+            .collect();
+        Ok(updates)
     }
 
     /// Terminal function of the `Sync` struct. Returns a list of all
@@ -79,12 +114,8 @@ impl<'a> Sync<'a> {
     pub async fn get_updates(self) -> Result<Vec<Update>> {
         let folders = self.api.course_folders(self.course_id).await?;
         let folders: Vec<(u32, String)> = folders
-            .as_array()
-            .ok_or(Error::NoFoldersFoundInCourse {
-                url: self.fm.url().to_string(),
-            })?
-            .into_iter()
-            .filter_map(|v| v.to_remote_folder(&self.remote_dir))
+            .iter()
+            .filter_map(|v| Some((v.id, v.remote_dir(&self.remote_dir)?)))
             .collect();
 
         if folders.is_empty() {
@@ -102,58 +133,236 @@ impl<'a> Sync<'a> {
 
         let local_dir = self.fm.local_dir();
         let loloupdates = futures::stream::iter(futures)
-            .buffer_unordered(10)
+            .buffer_unordered(self.listings)
             .map(|files| self.get_folder_updates(files, &local_dir));
         let t: Vec<Result<Vec<Update>>> = loloupdates.collect::<Vec<_>>().await;
         let t: Result<Vec<Vec<_>>> = t.into_iter().collect();
         Ok(t?.into_iter().flatten().collect())
     }
 
+    /// Downloads the updates (if `download` is set) at most `jobs` at
+    /// a time, records every successful download in `state`, and
+    /// reports the updates to `out`. Returns the errors of failed
+    /// downloads, which do not stop the other downloads, along with
+    /// the url of the folder map they belong to.
     pub async fn run(
         api: &Api,
         updates: Vec<Update>,
+        state: &mut State,
+        jobs: usize,
         download: bool,
-    ) -> Result<()> {
+        out: &Output,
+    ) -> Vec<(String, Error)> {
+        // (folder map url, file id, download url, file)
         let mut downloads = vec![];
-
         let mut updates = updates
             .into_iter()
             .map(|mut v| {
-                if let Some((url, local_path)) = mem::take(&mut v.download) {
-                    downloads.push(api.clone().download(url, local_path))
+                if let Some(url) = mem::take(&mut v.download) {
+                    let folder_url = v.folder_url.clone();
+                    downloads.push((
+                        folder_url,
+                        v.file_id,
+                        url,
+                        v.file.clone(),
+                    ));
                 }
                 v
             })
             .collect::<Vec<Update>>();
 
-        let user_courses = api.courses().await?.to_value_vec();
-        let course_hash: HashMap<u32, &str> = user_courses
-            .iter()
-            .map(|j| {
-                let course_id = j["id"].to_u32();
-                let course_name = j["name"].to_str();
-                (course_id, course_name)
-            })
-            .collect();
+        let course_hash = course_names(api, &updates).await;
 
         // sort updates by module name
         updates.sort_by(|a, b| {
             course_hash.get(&a.course_id).cmp(&course_hash.get(&b.course_id))
         });
 
-        display_updates(&updates, &course_hash);
-        if !download && !updates.is_empty() {
-            println!("! Fetch only. Nothing downloaded.");
+        if !out.is_text() {
+            for update in &updates {
+                let course_name =
+                    course_hash.get(&update.course_id).map(String::as_str);
+                out.emit(&Event::Update { course_name, update });
+            }
+        } else {
+            display_updates(&updates, &course_hash);
+            if !download && !updates.is_empty() {
+                println!("! Fetch only. Nothing downloaded.");
+            }
+        }
+        // bars are only drawn once the updates are printed.
+        let bytes = downloads.iter().map(|v| v.3.size).sum();
+        let progress = Progress::new(downloads.len(), bytes, !out.is_text());
+        let progress = &progress;
+        let downloads = downloads.into_iter().map(
+            |(folder_url, id, url, file)| async move {
+                let name = file.remote_path.to_string_lossy().to_string();
+                let bar = progress.file(&name, file.size);
+                let result = download_file(api, url, file, bar).await;
+                (folder_url, result.map(|file| (id, file)))
+            },
+        );
+        let results = api::resolve(downloads, jobs).await;
+        progress.finish();
+        let mut errors = vec![];
+        for (folder_url, result) in results {
+            match result {
+                Ok((file_id, file)) => state.insert(&folder_url, file_id, file),
+                Err(e) => errors.push((folder_url, e)),
+            }
         }
-        try_join_all(downloads).await?;
-        Ok(())
+        errors
     }
 }
 
-fn display_updates(updates: &Vec<Update>, course_names: &HashMap<u32, &str>) {
+/// Names of the courses of `updates`, by course id. They are only
+/// displayed, so a failed lookup leaves them out instead of stopping
+/// the downloads.
+async fn course_names(api: &Api, updates: &[Update]) -> HashMap<u32, String> {
     if updates.is_empty() {
-        println!("No new files found. All up to date!");
+        return HashMap::new();
+    }
+    let lookup = async {
+        // concluded courses are only looked up if one is tracked.
+        let courses = api.courses(&CourseFilter::default()).await?;
+        let known = |v: &Update| courses.iter().any(|c| c.id == v.course_id);
+        match updates.iter().all(known) {
+            true => Ok(courses),
+            false => api.courses(&CourseFilter::all()).await,
+        }
+    };
+    match lookup.await {
+        Ok(courses) => {
+            let names = courses.into_iter().map(|v| Some((v.id, v.name?)));
+            names.flatten().collect()
+        }
+        Err(e) => {
+            log::warn!("[SYNC] unable to fetch course names: {e}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Downloads a file to its local path, creating the directories on
+/// the way there.
+async fn download_file(
+    api: &Api,
+    url: String,
+    file: FileState,
+    progress: FileProgress,
+) -> Result<FileState> {
+    let path = file.local_path.clone();
+    let result = match path.parent() {
+        Some(parent) => tokio::fs::create_dir_all(parent).await,
+        None => Ok(()),
+    };
+    let result = match result {
+        Ok(_) => api.clone().download(url, path, file.size, &progress).await,
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(_) => {
+            set_mtime(&file);
+            progress.finish();
+        }
+        Err(_) => progress.fail(),
+    }
+    result.map(|_| file)
+}
+
+/// Sets the mtime of a downloaded file to when it was last modified on
+/// Canvas, so that local files sort by their date on Canvas.
+fn set_mtime(file: &FileState) {
+    let Ok(time) = humantime::parse_rfc3339_weak(&file.updated_at) else {
         return;
+    };
+    let mtime = FileTime::from_system_time(time);
+    if let Err(e) = filetime::set_file_mtime(&file.local_path, mtime) {
+        log::warn!("[SYNC] unable to set mtime of {:?}: {e}", file.local_path);
+    }
+}
+
+/// Prints what `pull` would do with each update: where it would be
+/// downloaded to, and whether it is a new download, an overwrite, or
+/// skipped. Touches nothing on disk.
+pub fn display_plan(updates: &[Update]) {
+    if updates.is_empty() {
+        println!("No new or modified files found. All up to date!");
+        return;
+    }
+    // (files, bytes) of new downloads, overwrites and skips.
+    let mut totals = [(0, 0); 3];
+    for update in updates {
+        let (i, action) = match (&update.skip, update.kind) {
+            (Some(_), _) => (2, "skip"),
+            (None, UpdateKind::New) => (0, "new"),
+            (None, UpdateKind::Modified) => (1, "overwrite"),
+        };
+        let size = update.file.size;
+        totals[i].0 += 1;
+        totals[i].1 += size;
+        let remote_path = update.remote_path().to_string_lossy();
+        println!("{action:<9} {:>9}  {remote_path}", human_size(size));
+        match &update.skip {
+            Some(reason) => println!("{:21}({reason})", ""),
+            None => println!(
+                "{:21}-> {}",
+                "",
+                update.file.local_path.to_string_lossy()
+            ),
+        }
+    }
+    let [new, overwrite, skip] = totals.map(|(n, v)| (n, human_size(v)));
+    println!(
+        "\nTotal: {} new ({}), {} overwrite ({}), {} skip ({})",
+        new.0, new.1, overwrite.0, overwrite.1, skip.0, skip.1
+    );
+    println!("! Dry run. Nothing downloaded.");
+}
+
+/// Decides whether a remote `file` needs to be downloaded, given what
+/// was recorded the last time it was downloaded (if ever). Returns
+/// `None` if the local file is up to date, or if it was deleted
+/// locally after being downloaded to the same path.
+fn compare(
+    file: &FileState,
+    prev: Option<&FileState>,
+    modified_at: Option<SystemTime>,
+) -> Option<UpdateKind> {
+    // a file renamed on Canvas, or a folder map moved locally, has a
+    // new target that was never downloaded to.
+    let prev = prev.filter(|v| v.local_path == file.local_path);
+    let meta = match file.local_path.metadata() {
+        Ok(v) if v.is_file() => v,
+        _ if prev.is_some() => return None,
+        _ => return Some(UpdateKind::New),
+    };
+    let changed = match prev {
+        Some(prev) => {
+            prev.size != file.size || prev.updated_at != file.updated_at
+        }
+        // downloaded without a record: fall back to file metadata. Files
+        // downloaded by canvas-sync carry their mtime on Canvas. A local
+        // copy that is larger and newer was edited by the user, but one
+        // that is shorter was cut off halfway through a download.
+        None => {
+            let newer = match (modified_at, meta.modified()) {
+                (Some(remote), Ok(local)) => remote > local,
+                _ => false,
+            };
+            newer || meta.len() < file.size
+        }
+    };
+    changed.then_some(UpdateKind::Modified)
+}
+
+/// Prints the updates grouped by course, followed by the ones left out
+/// by filters along with the reason.
+fn display_updates(updates: &[Update], course_names: &HashMap<u32, String>) {
+    let (skipped, updates): (Vec<_>, Vec<_>) =
+        updates.iter().partition(|v| v.skip.is_some());
+    if updates.is_empty() {
+        println!("No new or modified files found. All up to date!");
     }
     let mut prev_id = 0;
     for update in updates {
@@ -167,6 +376,71 @@ fn display_updates(updates: &Vec<Update>, course_names: &HashMap<u32, &str>) {
                 ),
             }
         }
-        println!("  + {}", update.remote_path.to_string_lossy());
+        let sign = match update.kind {
+            UpdateKind::New => '+',
+            UpdateKind::Modified => '~',
+        };
+        println!("  {sign} {}", update.remote_path().to_string_lossy());
+    }
+    if skipped.is_empty() {
+        return;
+    }
+    println!("Skipped {} file(s):", skipped.len());
+    for update in skipped {
+        let reason = update.skip.as_deref().unwrap_or_default();
+        let path = update.remote_path().to_string_lossy();
+        println!("  - {path} ({reason})");
+    }
+}
+
+#[test]
+fn test_compare() {
+    let dir = std::env::temp_dir().join("canvas-sync-test-compare");
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = FileState {
+        remote_path: "notes.pdf".into(),
+        size: 5,
+        updated_at: "2023-01-09T02:00:00Z".to_string(),
+        local_path: dir.join("notes.pdf"),
+    };
+    let modified_at = humantime::parse_rfc3339_weak(&file.updated_at).ok();
+    std::fs::remove_file(&file.local_path).ok();
+    assert_eq!(compare(&file, None, modified_at), Some(UpdateKind::New));
+    assert_eq!(compare(&file, Some(&file), modified_at), None);
+
+    // recorded at another path: renamed on Canvas, or moved locally.
+    let mut moved = file.clone();
+    moved.local_path = dir.join("old name.pdf");
+    assert_eq!(
+        compare(&file, Some(&moved), modified_at),
+        Some(UpdateKind::New)
+    );
+
+    std::fs::write(&file.local_path, "hello").unwrap();
+    set_mtime(&file);
+    assert_eq!(compare(&file, None, modified_at), None);
+    assert_eq!(compare(&file, Some(&file), modified_at), None);
+
+    let mut newer = file.clone();
+    newer.updated_at = "2023-01-10T02:00:00Z".to_string();
+    let newer_at = humantime::parse_rfc3339_weak(&newer.updated_at).ok();
+    assert_eq!(compare(&newer, None, newer_at), Some(UpdateKind::Modified));
+    assert_eq!(
+        compare(&newer, Some(&file), newer_at),
+        Some(UpdateKind::Modified)
+    );
+
+    // an annotated copy: larger, and modified after it was on Canvas.
+    std::fs::write(&file.local_path, "hello, with notes").unwrap();
+    assert_eq!(compare(&file, None, modified_at), None);
+
+    // cut off halfway through a download, after it was on Canvas.
+    for partial in ["hel", ""] {
+        std::fs::write(&file.local_path, partial).unwrap();
+        assert_eq!(
+            compare(&file, None, modified_at),
+            Some(UpdateKind::Modified)
+        );
     }
+    std::fs::remove_dir_all(&dir).ok();
 }
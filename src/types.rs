@@ -1,26 +1,64 @@
 use crate::error::Result;
-use crate::string::parse_url;
+use crate::filter::Filters;
+use crate::state::FileState;
+use crate::string::{normalize_filename, parse_url};
 use crate::traits::*;
 use crate::BINARY_NAME;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-#[derive(Debug)]
+/// What happened to a remote file since it was last synced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateKind {
+    /// Not found locally.
+    New,
+    /// Found locally, but changed on Canvas since.
+    Modified,
+}
+
+#[derive(Debug, Serialize)]
 pub struct Update {
     pub course_id: u32,
-    /// PathBuf because it needs to be `remote_dir.join(&filename)`
-    pub remote_path: PathBuf,
-    /// url -> download destination file
-    pub download: Option<(String, PathBuf)>,
+    /// url of the folder map tracking this file.
+    pub folder_url: String,
+    pub file_id: u32,
+    pub kind: UpdateKind,
+    /// what gets recorded in the sync state once downloaded.
+    #[serde(flatten)]
+    pub file: FileState,
+    /// url to download the file from
+    #[serde(skip)]
+    pub download: Option<String>,
+    /// why the file is left out of downloads by the folder's filters.
+    #[serde(rename = "skip_reason")]
+    pub skip: Option<String>,
 }
 
 impl Update {
     pub fn new(
         course_id: u32,
-        remote_path: PathBuf,
-        download: Option<(String, PathBuf)>,
+        folder_url: &str,
+        file_id: u32,
+        kind: UpdateKind,
+        file: FileState,
     ) -> Self {
-        Self { course_id, remote_path, download }
+        let folder_url = folder_url.to_string();
+        Self {
+            course_id,
+            folder_url,
+            file_id,
+            kind,
+            file,
+            download: None,
+            skip: None,
+        }
+    }
+
+    /// Path relative to the tracked remote folder.
+    pub fn remote_path(&self) -> &Path {
+        &self.file.remote_path
     }
 }
 
@@ -31,19 +69,35 @@ pub struct FolderMap {
     url: String,
     /// local dir to track the folder that the url points to.
     path: String,
+    #[serde(flatten)]
+    filters: Filters,
     /// base path (taken from the config)
+    #[serde(skip)]
     base: Option<String>,
+    /// url of the Canvas instance (taken from the config)
+    #[serde(skip)]
+    base_url: String,
 }
 
 impl FolderMap {
+    pub fn new(url: &str, path: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            path: path.to_string(),
+            filters: Filters::default(),
+            base: None,
+            base_url: String::new(),
+        }
+    }
+
     /// parse course_id from folder map's url
     pub fn course_id(&self) -> Result<u32> {
-        parse_url(&self.url).map(|v| v.0)
+        parse_url(&self.url, &self.base_url).map(|v| v.0)
     }
 
     /// parse remote directory from folder map's url
     pub fn remote_dir(&self) -> Result<String> {
-        parse_url(&self.url).map(|v| v.1)
+        parse_url(&self.url, &self.base_url).map(|v| v.1)
     }
 
     /// get local directory that tracks the url folder.
@@ -64,31 +118,38 @@ impl FolderMap {
         }
     }
 
-    /// only to be used when parsing the config file for the first time
-    pub fn set(&mut self, base: Option<String>) {
+    /// only to be used when parsing the config file for the first time,
+    /// or when adding a new folder map to it
+    pub fn set(&mut self, base: Option<String>, base_url: &str) {
         if let Ok(url) = urlencoding::decode(&self.url) {
             self.url = url.to_string()
         }
-        self.base = base
+        self.base = base;
+        self.base_url = base_url.to_string();
     }
 
     pub fn url(&self) -> &str {
         &self.url
     }
+
+    pub fn filters(&self) -> &Filters {
+        &self.filters
+    }
 }
 
 /// Corresponds to one `Profile` over on canvas.
 /// https://canvas.instructure.com/doc/api/users.html#Profile
-#[derive(Debug, Deserialize)]
-pub struct User {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
     id: u32,
     name: String,
-    integration_id: String,
-    primary_email: String,
+    integration_id: Option<String>,
+    primary_email: Option<String>,
 }
 
-impl User {
+impl Profile {
     pub fn display(&self) {
+        let or_blank = |v: &Option<String>| v.clone().unwrap_or_default();
         println!(
             "\
 {BINARY_NAME}
@@ -98,18 +159,168 @@ user data
   * name:      {}
   * email:     {}
   * matric:    {}",
-            self.id, self.name, self.primary_email, self.integration_id
+            self.id,
+            self.name,
+            or_blank(&self.primary_email),
+            or_blank(&self.integration_id)
         )
     }
 }
 
-impl From<serde_json::Value> for User {
-    fn from(json: serde_json::Value) -> Self {
-        Self {
-            id: json["id"].to_u32(),
-            integration_id: json["integration_id"].to_str().to_string(),
-            primary_email: json["primary_email"].to_str().to_string(),
-            name: json["name"].to_str().to_string(),
+/// Corresponds to one `Course` over on canvas.
+/// https://canvas.instructure.com/doc/api/courses.html#Course
+#[derive(Debug, Deserialize)]
+pub struct Course {
+    pub id: u32,
+    /// absent for courses that are no longer accessible.
+    pub name: Option<String>,
+    pub course_code: Option<String>,
+    /// only sent when requested with `include[]=term`.
+    pub term: Option<Term>,
+}
+
+/// Corresponds to one `EnrollmentTerm` over on canvas.
+/// https://canvas.instructure.com/doc/api/enrollment_terms.html
+#[derive(Debug, Deserialize)]
+pub struct Term {
+    pub name: String,
+    pub start_at: Option<String>,
+    pub end_at: Option<String>,
+}
+
+impl Term {
+    /// Whether `now` falls within the term. A term without a start or
+    /// end date is taken to be open on that side.
+    pub fn contains(&self, now: SystemTime) -> bool {
+        let parse = |v: &Option<String>| {
+            v.as_deref().and_then(|v| humantime::parse_rfc3339_weak(v).ok())
+        };
+        parse(&self.start_at).is_none_or(|v| v <= now)
+            && parse(&self.end_at).is_none_or(|v| now <= v)
+    }
+}
+
+/// Which of the user's courses to ask Canvas for. By default, only
+/// courses the user is actively enrolled in.
+#[derive(Debug, Default, Clone, clap::Args)]
+pub struct CourseFilter {
+    /// Include courses of past enrollments too
+    #[arg(short, long)]
+    pub all: bool,
+    /// Only courses of the enrollment term with this name
+    #[arg(long)]
+    pub term: Option<String>,
+    /// Only courses of the current enrollment term
+    #[arg(short, long)]
+    pub current_term: bool,
+}
+
+impl CourseFilter {
+    /// Every course the user has ever been in.
+    pub fn all() -> Self {
+        Self { all: true, ..Self::default() }
+    }
+
+    /// Whether `course` passes the filters that Canvas cannot apply
+    /// on its end.
+    pub fn matches(&self, course: &Course, now: SystemTime) -> bool {
+        let term = course.term.as_ref();
+        if let Some(name) = &self.term {
+            if !term.is_some_and(|t| t.name.eq_ignore_ascii_case(name)) {
+                return false;
+            }
         }
+        !self.current_term || term.is_none_or(|t| t.contains(now))
     }
 }
+
+/// Corresponds to one `Folder` over on canvas.
+/// https://canvas.instructure.com/doc/api/files.html#Folder
+#[derive(Debug, Deserialize)]
+pub struct Folder {
+    pub id: u32,
+    /// `course files/path/of/actual/folder`
+    pub full_name: String,
+}
+
+impl Folder {
+    /// Path of this folder relative to `tracked_remote_dir`, or `None`
+    /// if it is not inside it.
+    ///
+    /// if tracked_remote_dir is blank, then the tracking begins from
+    /// the root folder.
+    pub fn remote_dir(&self, tracked_remote_dir: &str) -> Option<String> {
+        let t = tracked_remote_dir;
+        // the root folder itself, holding the top level files.
+        if self.full_name == "course files" {
+            return t.is_empty().then(String::new);
+        }
+        let remote_dir = self.full_name.strip_prefix("course files/")?;
+
+        if tracked_remote_dir.is_empty() {
+            return Some(remote_dir.to_string());
+        }
+
+        if remote_dir.eq(t) {
+            return Some("".to_string());
+        }
+
+        if remote_dir.starts_with(t) && remote_dir[t.len()..].starts_with('/') {
+            return Some(remote_dir[t.len() + 1..].to_string());
+        }
+
+        None
+    }
+}
+
+/// Corresponds to one `File` over on canvas.
+/// https://canvas.instructure.com/doc/api/files.html#File
+#[derive(Debug, Deserialize)]
+pub struct File {
+    pub id: u32,
+    pub display_name: String,
+    pub size: u64,
+    /// download url, left out or blank for files locked to the user.
+    pub url: Option<String>,
+    pub updated_at: String,
+    pub modified_at: Option<String>,
+    /// MIME type, e.g. `application/pdf`.
+    #[serde(rename = "content-type", default)]
+    pub content_type: String,
+}
+
+impl File {
+    pub fn normalized_filename(&self) -> String {
+        normalize_filename(&self.display_name)
+    }
+
+    /// Last time the file's contents changed on Canvas. Uses
+    /// `modified_at`, falling back to `updated_at`.
+    pub fn last_modified(&self) -> &str {
+        self.modified_at.as_deref().unwrap_or(&self.updated_at)
+    }
+
+    /// `last_modified`, parsed.
+    pub fn last_modified_time(&self) -> Option<SystemTime> {
+        humantime::parse_rfc3339_weak(self.last_modified()).ok()
+    }
+
+    /// Url to download the file from, if the user is allowed to.
+    pub fn download_url(&self) -> Option<&str> {
+        self.url.as_deref().filter(|v| !v.is_empty())
+    }
+}
+
+#[test]
+fn test_folder_remote_dir() {
+    let folder =
+        |full_name: &str| Folder { id: 1, full_name: full_name.to_string() };
+    let lec = folder("course files/Lectures/Week 1");
+    assert_eq!(lec.remote_dir(""), Some("Lectures/Week 1".to_string()));
+    assert_eq!(lec.remote_dir("Lectures"), Some("Week 1".to_string()));
+    assert_eq!(lec.remote_dir("Lectures/Week 1"), Some("".to_string()));
+    assert_eq!(lec.remote_dir("Lecture"), None);
+    let root = folder("course files");
+    assert_eq!(root.remote_dir(""), Some("".to_string()));
+    assert_eq!(root.remote_dir("Lectures"), None);
+}